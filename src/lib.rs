@@ -21,19 +21,12 @@
 //!
 //!    let source = "@hello-man(name: type = value, name2: type2, name3: type3 = value3)";
 //!
-//!    for (i, c) in source.char_indices() {
-//!        builder.test(c, i);
-//!    }
-//!    let finals = builder.finalize_with_source(source);
+//!    // drives the scan internally, parallelizing across patterns:
+//!    let finals = builder.run(source);
 //!    eprintln!("{:#?}", finals);
 //! ```
 
-use std::{
-    fmt,
-    iter::{Cycle, Peekable},
-    ops::Range,
-    str::Chars,
-};
+use std::{fmt, ops::Range};
 
 use rayon::prelude::*;
 
@@ -59,6 +52,33 @@ impl<'s, T: TokenKind> CondexBuilder<'s, T> {
             .par_iter_mut()
             .for_each(|(_, condex)| condex.par_iter_mut().for_each(|con| con.test(c, i)));
     }
+    /// Feeds the whole `source` through in one call and returns the matched
+    /// spans as trimmed substrings. Parallelizes once across `condexes`
+    /// rather than re-forking per character: each pattern walks its own
+    /// `char_indices` serially. Streaming callers that can't materialize
+    /// the whole source up front should keep using [`Self::test`].
+    #[inline]
+    pub fn run(mut self, source: &'s str) -> Vec<(T, Vec<CondexResultStr<'s>>)> {
+        self.drive(source);
+        self.finalize_with_source(source)
+    }
+    /// Like [`Self::run`], but returns raw byte-offset spans instead of
+    /// substrings.
+    #[inline]
+    pub fn run_spans(mut self, source: &'s str) -> Vec<(T, Vec<CondexResult>)> {
+        self.drive(source);
+        self.finalize()
+    }
+    #[inline]
+    fn drive(&mut self, source: &str) {
+        self.condexes.par_iter_mut().for_each(|(_, condex)| {
+            for (i, c) in source.char_indices() {
+                for con in condex.iter_mut() {
+                    con.test(c, i);
+                }
+            }
+        });
+    }
     #[inline]
     pub fn finalize(self) -> Vec<(T, Vec<CondexResult>)> {
         self.condexes
@@ -92,6 +112,76 @@ impl<'s, T: TokenKind> CondexBuilder<'s, T> {
             })
             .collect()
     }
+    /// Like [`Self::finalize_with_source`], but also reports, for every
+    /// `Condex` that never completed a single result, how far it got and
+    /// what it was still expecting — so a pattern that never matches
+    /// anything isn't just silently dropped.
+    #[inline]
+    pub fn finalize_with_diagnostics(self, source: &'s str) -> CondexDiagnosed<'s, T> {
+        let diagnostics = self
+            .condexes
+            .iter()
+            .flat_map(|(&kind, condex)| {
+                condex.iter().filter(|con| con.results.is_empty()).map(move |con| {
+                    let (expected, negated) = con
+                        .steps
+                        .get(con.furthest_step)
+                        .map(expected_chars)
+                        .unwrap_or_default();
+                    CondexDiagnostic {
+                        kind,
+                        offset: con.furthest_i,
+                        rendered: render_caret(source, con.furthest_i, &expected, negated),
+                    }
+                })
+            })
+            .collect();
+        (self.finalize_with_source(source), diagnostics)
+    }
+}
+
+/// A single pattern that never reached a complete match: where it got stuck
+/// and what it was still expecting at that point.
+#[derive(Debug, Clone)]
+pub struct CondexDiagnostic<T: TokenKind> {
+    pub kind: T,
+    pub offset: usize,
+    pub rendered: String,
+}
+pub type CondexDiagnosed<'s, T> = (Vec<(T, Vec<CondexResultStr<'s>>)>, Vec<CondexDiagnostic<T>>);
+
+/// Renders the source line containing `offset`, followed by a caret line
+/// pointing at the column `offset` falls on and a message listing the chars
+/// that were expected there. `negated` flips the message for a `[^...]`
+/// class, whose expectation is "anything but", not "one of".
+fn render_caret(source: &str, offset: usize, expected: &[char], negated: bool) -> String {
+    let mut line_start = 0;
+    let mut line_end = source.len();
+    for (i, c) in source.char_indices() {
+        if c == '\n' {
+            if i >= offset {
+                line_end = i;
+                break;
+            }
+            line_start = i + 1;
+        }
+    }
+    let line = &source[line_start..line_end];
+    let column = source[line_start..offset].chars().count();
+
+    let mut rendered = String::new();
+    rendered.push_str(line);
+    rendered.push('\n');
+    rendered.extend(std::iter::repeat_n(' ', column));
+    rendered.push('^');
+    if negated {
+        rendered.push_str(" expected anything but [");
+    } else {
+        rendered.push_str(" expected one of [");
+    }
+    rendered.extend(expected.iter());
+    rendered.push(']');
+    rendered
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -104,23 +194,176 @@ pub type CondexComponent<'s> = Vec<Condex<'s>>;
 pub type CondexResult = Vec<Span>;
 pub type CondexResultStr<'s> = Vec<&'s str>;
 
+/// A single compiled unit of a condex pattern: either a plain character or a
+/// `[...]` class, tagged with whether a preceding `-` marks it as the end of
+/// a recorded span.
+#[derive(Debug, Clone)]
+enum StepKind {
+    Literal(char),
+    /// A `[...]` class. `negated` is set by a leading `^`, so the step
+    /// matches any char *not* in `set` instead.
+    OneOf { set: Box<[char]>, negated: bool },
+}
+
+/// How many times a step may consume input before yielding to the next one.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Quantifier {
+    One,
+    Optional,
+    ZeroOrMore,
+    OneOrMore,
+}
+
+#[derive(Debug, Clone)]
+struct Step {
+    kind: StepKind,
+    record: bool,
+    quantifier: Quantifier,
+    /// Set on a `\ ` literal: unlike an ordinary space, it must not be
+    /// skipped by `Condex::test`'s usual space-skipping.
+    literal_space: bool,
+}
+
+/// Parses a condex pattern string into its step machine once, up front, so
+/// that `Condex::test` never has to re-walk the pattern text.
+fn compile(pattern: &str) -> Box<[Step]> {
+    let mut steps = Vec::new();
+    let mut chars = pattern.chars().peekable();
+    let mut pending_record = false;
+    while let Some(c) = chars.next() {
+        match c {
+            ' ' => continue,
+            '-' => pending_record = true,
+            '\\' => {
+                // A backslash escapes the next char, stripping any special
+                // meaning it would otherwise have (`-`, `[`, `]`, ` `, `\`).
+                let escaped = chars.next().unwrap_or('\\');
+                steps.push(Step {
+                    kind: StepKind::Literal(escaped),
+                    record: pending_record,
+                    quantifier: parse_quantifier(&mut chars),
+                    literal_space: escaped == ' ',
+                });
+                pending_record = false;
+            }
+            '[' => {
+                let negated = chars.peek() == Some(&'^');
+                if negated {
+                    chars.next();
+                }
+                let mut set = Vec::new();
+                while let Some(&next_c) = chars.peek() {
+                    if next_c == ']' {
+                        break;
+                    }
+                    set.push(next_c);
+                    chars.next();
+                }
+                chars.next(); // consume the closing ']'
+                steps.push(Step {
+                    kind: StepKind::OneOf {
+                        set: set.into_boxed_slice(),
+                        negated,
+                    },
+                    record: pending_record,
+                    quantifier: parse_quantifier(&mut chars),
+                    literal_space: false,
+                });
+                pending_record = false;
+            }
+            other => {
+                steps.push(Step {
+                    kind: StepKind::Literal(other),
+                    record: pending_record,
+                    quantifier: parse_quantifier(&mut chars),
+                    literal_space: false,
+                });
+                pending_record = false;
+            }
+        }
+    }
+    steps.into_boxed_slice()
+}
+
+/// Consumes a trailing `*`, `+`, or `?` following a step, if present.
+fn parse_quantifier(chars: &mut std::iter::Peekable<std::str::Chars>) -> Quantifier {
+    match chars.peek() {
+        Some('*') => {
+            chars.next();
+            Quantifier::ZeroOrMore
+        }
+        Some('+') => {
+            chars.next();
+            Quantifier::OneOrMore
+        }
+        Some('?') => {
+            chars.next();
+            Quantifier::Optional
+        }
+        _ => Quantifier::One,
+    }
+}
+
+/// The chars a step will accept, and whether that's a negated (`[^...]`)
+/// class, for use in diagnostics.
+fn expected_chars(step: &Step) -> (Box<[char]>, bool) {
+    match &step.kind {
+        StepKind::Literal(c) => (Box::from([*c]), false),
+        StepKind::OneOf { set, negated } => (set.clone(), *negated),
+    }
+}
+
+fn step_matches(step: &Step, c: char) -> bool {
+    match &step.kind {
+        StepKind::Literal(target) => c == *target,
+        StepKind::OneOf { set, negated } => set.contains(&c) != *negated,
+    }
+}
+
+/// What happened when a single input char was offered to the current step.
+enum StepOutcome {
+    /// The char was consumed by the current step.
+    Consumed,
+    /// An unescaped space hit a step that isn't waiting for one; it's
+    /// dropped without affecting any state.
+    Ignored,
+    /// The current step yielded without consuming; retry the same char
+    /// against the next step.
+    Retry,
+    /// A required step failed to match at all; the whole pattern resets.
+    Dead,
+}
+
 #[derive(Clone)]
 pub struct Condex<'s> {
-    condex: Peekable<Cycle<Chars<'s>>>,
+    pattern: &'s str,
+    steps: Box<[Step]>,
+    cursor: usize,
     current_state: CondexState,
     prev_i: usize,
-    condex_len: usize,
     result_len: usize,
     result: CondexResult,
     results: Vec<CondexResult>,
-    or_conditions: Vec<char>,
+    furthest_i: usize,
+    /// Index into `steps` of whatever was still expected at `furthest_i`.
+    /// Kept as an index rather than the char set itself so the hot path
+    /// (`try_step`/`advance_cursor`, run on essentially every input char)
+    /// never allocates; `expected_chars` is only materialized lazily, in
+    /// `finalize_with_diagnostics`, for callers that actually want it.
+    furthest_step: usize,
+    quant_matched: bool,
 }
 impl<'s> std::fmt::Debug for Condex<'s> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (expected, negated) = self
+            .steps
+            .get(self.furthest_step)
+            .map(expected_chars)
+            .unwrap_or_default();
         write!(
             f,
-            "state: {:?}\npending index: {}\nresult len: {}\npending: {:#?}\nresults: {:#?}\nor conditions: {:#?}",
-            self.current_state, self.prev_i, self.result_len, self.result, self.results, self.or_conditions
+            "pattern: {:?}\nstate: {:?}\ncursor: {}\npending index: {}\nresult len: {}\npending: {:#?}\nresults: {:#?}\nfurthest: {} expecting {:?} (negated: {})",
+            self.pattern, self.current_state, self.cursor, self.prev_i, self.result_len, self.result, self.results, self.furthest_i, expected, negated
         )
     }
 }
@@ -136,125 +379,118 @@ impl<'s> Condex<'s> {
     }
     #[inline]
     fn _new(condex: &'s str) -> Self {
-        let temp = condex.chars();
-        let mut result_len = 0;
-        let mut condex_len = 0;
-        for c in temp {
-            if c == '-' {
-                result_len += 1;
-            }
-            condex_len += 1;
-        }
+        let steps = compile(condex);
+        let result_len = steps.iter().filter(|step| step.record).count();
         Self {
-            condex: condex.chars().cycle().peekable(),
+            pattern: condex,
+            steps,
+            cursor: 0,
             current_state: CondexState::Await,
             prev_i: 0,
-            condex_len,
             result_len,
             result: Vec::with_capacity(result_len),
             results: Vec::new(),
-            or_conditions: Vec::new(),
+            furthest_i: 0,
+            furthest_step: 0,
+            quant_matched: false,
         }
     }
     #[inline]
     pub fn test(&mut self, c: char, i: usize) {
-        if c == ' ' {
-            // skip a space
-            return;
-        }
-        let target_c = self.next();
-        if match self.current_state {
-            CondexState::Await => {
-                if self.or_conditions.is_empty() {
-                    c == target_c
-                } else {
-                    self.or_conditions.contains(&c)
-                }
-            }
-            CondexState::Record => {
-                if if self.or_conditions.is_empty() {
-                    c == target_c
-                } else {
-                    self.or_conditions.contains(&c)
-                } {
-                    self.result.push(self.prev_i..i);
-                    if self.result.len() >= self.result_len {
-                        self.results.push(self.result.clone());
-                        self.result.clear();
-                    }
-                    true
-                } else {
-                    false
-                }
+        // Bounded so a run of all-optional steps can't retry forever without
+        // ever consuming `c`.
+        for _ in 0..=self.steps.len() {
+            match self.try_step(c, i) {
+                StepOutcome::Consumed | StepOutcome::Ignored => return,
+                StepOutcome::Retry => continue,
+                StepOutcome::Dead => break,
             }
-        } {
-            self.prev_i = i + 1;
-            self.or_conditions.clear();
-            self.reset_state();
-            self.condex_next();
-        } else {
-            let _ = self.condex.by_ref().skip(self.condex_len);
         }
+        self.cursor = 0;
+        self.quant_matched = false;
     }
-    #[inline]
-    fn next(&mut self) -> char {
-        let mut c = self.condex_peek();
-        if c == ' ' {
-            // skip a space
-            loop {
-                c = self.condex_peek();
-                if c != ' ' {
-                    break;
-                } else {
-                    self.condex_next();
+    /// Offers `c` to the step at `cursor`, advancing (or not) according to
+    /// that step's quantifier.
+    fn try_step(&mut self, c: char, i: usize) -> StepOutcome {
+        let step = &self.steps[self.cursor];
+        // An unescaped space is just a separator for every step except the
+        // one it was specifically escaped for; it must be re-checked per
+        // step the retry loop lands on, not just the step test() started at.
+        let ignorable_space = c == ' ' && !step.literal_space;
+        if !ignorable_space && step_matches(step, c) {
+            match step.quantifier {
+                Quantifier::One | Quantifier::Optional => {
+                    self.commit_step(step.record, i);
+                    StepOutcome::Consumed
+                }
+                Quantifier::ZeroOrMore | Quantifier::OneOrMore => {
+                    self.quant_matched = true;
+                    self.note_progress(i, self.cursor);
+                    StepOutcome::Consumed
                 }
             }
-        }
-
-        match c {
-            '-' => {
-                self.set_state(CondexState::Record);
-                self.condex_next();
-                self.next()
+        } else if step.quantifier == Quantifier::One
+            || (step.quantifier == Quantifier::OneOrMore && !self.quant_matched)
+        {
+            if ignorable_space {
+                StepOutcome::Ignored
+            } else {
+                StepOutcome::Dead
             }
-            '[' => {
-                self.or_conditions.clear();
-                self.condex_next();
-
-                loop {
-                    match self.condex_peek() {
-                        ']' => break,
-                        target_c => {
-                            self.or_conditions.push(target_c);
-                            self.condex_next();
-                        }
-                    }
-                }
-
-                if self.or_conditions.is_empty() {
-                    self.condex_next()
-                } else {
-                    ']'
+        } else if ignorable_space
+            && matches!(step.quantifier, Quantifier::ZeroOrMore | Quantifier::OneOrMore)
+        {
+            // A space hitting a */+ step — whether or not it has matched
+            // anything yet — is just skipped, same as anywhere else; it
+            // must not be mistaken for the char that ends the run.
+            StepOutcome::Ignored
+        } else {
+            // A `?`/`*`/`+` step has run its course (possibly zero-length):
+            // close out any recorded span over the whole run and let the
+            // next step have a go at the same char.
+            self.quant_matched = false;
+            if step.record {
+                self.result.push(self.prev_i..i);
+                if self.result.len() >= self.result_len {
+                    self.results.push(std::mem::take(&mut self.result));
                 }
             }
-            _ => c,
+            self.prev_i = i;
+            self.advance_cursor(i);
+            StepOutcome::Retry
         }
     }
-    #[inline]
-    fn condex_peek(&mut self) -> char {
-        *self.condex.peek().unwrap()
-    }
-    #[inline]
-    fn condex_next(&mut self) -> char {
-        self.condex.next().unwrap()
+    /// Consumes `c` at position `i` for a single-shot step: records a span
+    /// if the step is a `-` boundary, then moves on to the next step.
+    fn commit_step(&mut self, record: bool, i: usize) {
+        self.current_state = if record {
+            CondexState::Record
+        } else {
+            CondexState::Await
+        };
+        if record {
+            self.result.push(self.prev_i..i);
+            if self.result.len() >= self.result_len {
+                self.results.push(std::mem::take(&mut self.result));
+            }
+        }
+        self.prev_i = i + 1;
+        self.advance_cursor(i);
     }
-    #[inline]
-    fn reset_state(&mut self) {
-        self.set_state(CondexState::Await);
+    fn advance_cursor(&mut self, i: usize) {
+        self.cursor = (self.cursor + 1) % self.steps.len();
+        self.note_progress(i, self.cursor);
     }
-    #[inline]
-    fn set_state(&mut self, state: CondexState) {
-        self.current_state = state;
+    /// Records `i` as the furthest position reached and `step` as the index
+    /// of whatever was still needed there, as long as it isn't a regression
+    /// — called on every char consumed, including repeated matches within a
+    /// `*`/`+` run. Stores just the index rather than resolving it to an
+    /// expected char set, so this stays allocation-free on the hot path.
+    fn note_progress(&mut self, i: usize, step: usize) {
+        if i >= self.furthest_i {
+            self.furthest_i = i;
+            self.furthest_step = step;
+        }
     }
 }
 
@@ -274,7 +510,6 @@ mod tests {
 
     #[test]
     fn it_works() {
-        // let test = r"#-(    {-:-[=,)]-[,)]} \{";
         let mut builder = CondexBuilder::new(&[
             (Token::TagName, &["@-("]),
             (Token::NameType, &["[(,]  -  :  - [,=]"]),
@@ -290,4 +525,184 @@ mod tests {
         let finals = builder.finalize_with_source(source);
         eprintln!("{:#?}", finals);
     }
+
+    #[test]
+    fn reports_diagnostics_for_incomplete_patterns() {
+        let mut builder = CondexBuilder::new(&[(Token::NameType, &["[(,]  -  :  - [,=]"])]);
+
+        let source = "name type = value";
+
+        for (i, c) in source.char_indices() {
+            builder.test(c, i);
+        }
+        let (finals, diagnostics) = builder.finalize_with_diagnostics(source);
+
+        assert!(finals[0].1.is_empty());
+        assert_eq!(diagnostics.len(), 1);
+        assert!(diagnostics[0].rendered.contains('^'));
+    }
+
+    #[test]
+    fn quantifiers_match_variable_length_runs() {
+        let mut builder =
+            CondexBuilder::new(&[(Token::Value, &["-[abcdefghijklmnopqrstuvwxyz]*[,)]"])]);
+
+        let source = "value,";
+
+        for (i, c) in source.char_indices() {
+            builder.test(c, i);
+        }
+        let finals = builder.finalize_with_source(source);
+
+        assert_eq!(finals[0].1, vec![vec!["value"]]);
+    }
+
+    #[test]
+    fn optional_quantifier_matches_with_or_without_the_step() {
+        let pattern = &["a?-b"];
+        let condexes = [(Token::TagName, &pattern[..])];
+
+        let mut with_a = CondexBuilder::new(&condexes);
+        for (i, c) in "ab".char_indices() {
+            with_a.test(c, i);
+        }
+        assert_eq!(with_a.finalize()[0].1.len(), 1);
+
+        let mut without_a = CondexBuilder::new(&condexes);
+        for (i, c) in "b".char_indices() {
+            without_a.test(c, i);
+        }
+        assert_eq!(without_a.finalize()[0].1.len(), 1);
+    }
+
+    #[test]
+    fn escaped_hyphen_matches_a_literal_hyphen() {
+        let mut builder = CondexBuilder::new(&[(Token::TagName, &[r"a-\-b"])]);
+
+        let source = "a-b";
+
+        for (i, c) in source.char_indices() {
+            builder.test(c, i);
+        }
+        let finals = builder.finalize();
+
+        assert_eq!(finals[0].1.len(), 1);
+    }
+
+    #[test]
+    fn escaped_brackets_match_literal_brackets() {
+        let mut builder = CondexBuilder::new(&[(Token::TagName, &[r"\[-x\]"])]);
+
+        let source = "[x]";
+
+        for (i, c) in source.char_indices() {
+            builder.test(c, i);
+        }
+        let finals = builder.finalize();
+
+        assert_eq!(finals[0].1.len(), 1);
+    }
+
+    #[test]
+    fn optional_step_falls_through_to_a_later_escaped_space() {
+        // A leading optional step must not swallow a space meant for a
+        // later `\ ` step before the retry chain ever reaches it.
+        let mut builder = CondexBuilder::new(&[(Token::TagName, &[r"a?\ -b"])]);
+
+        let source = " b";
+
+        for (i, c) in source.char_indices() {
+            builder.test(c, i);
+        }
+        let finals = builder.finalize();
+
+        assert_eq!(finals[0].1.len(), 1);
+    }
+
+    #[test]
+    fn a_space_inside_a_running_quantifier_does_not_split_the_capture() {
+        let mut builder =
+            CondexBuilder::new(&[(Token::Value, &["-[abcdefghijklmnopqrstuvwxyz]*[,)]"])]);
+
+        let source = "john doe,";
+
+        for (i, c) in source.char_indices() {
+            builder.test(c, i);
+        }
+        let finals = builder.finalize_with_source(source);
+
+        assert_eq!(finals[0].1, vec![vec!["john doe"]]);
+    }
+
+    #[test]
+    fn a_leading_space_before_a_quantifiers_first_match_is_ignored() {
+        let mut builder =
+            CondexBuilder::new(&[(Token::Value, &["-[abcdefghijklmnopqrstuvwxyz]*[,)]"])]);
+
+        let source = " value,";
+
+        for (i, c) in source.char_indices() {
+            builder.test(c, i);
+        }
+        let finals = builder.finalize_with_source(source);
+
+        assert_eq!(finals[0].1, vec![vec!["value"]]);
+    }
+
+    #[test]
+    fn negated_class_captures_until_a_delimiter() {
+        let mut builder = CondexBuilder::new(&[(Token::Value, &["-[^,)]+[,)]"])]);
+
+        let source = "value,";
+
+        for (i, c) in source.char_indices() {
+            builder.test(c, i);
+        }
+        let finals = builder.finalize_with_source(source);
+
+        assert_eq!(finals[0].1, vec![vec!["value"]]);
+    }
+
+    #[test]
+    fn diagnostics_for_a_negated_class_say_anything_but() {
+        let mut builder = CondexBuilder::new(&[(Token::Value, &["-[^,)]+[,)]"])]);
+
+        let source = "value";
+
+        for (i, c) in source.char_indices() {
+            builder.test(c, i);
+        }
+        let (_, diagnostics) = builder.finalize_with_diagnostics(source);
+
+        assert_eq!(diagnostics.len(), 1);
+        assert!(diagnostics[0].rendered.contains("expected anything but ["));
+    }
+
+    #[test]
+    fn empty_negated_class_matches_anything() {
+        let mut builder = CondexBuilder::new(&[(Token::Value, &["[^]"])]);
+
+        let source = "x";
+
+        for (i, c) in source.char_indices() {
+            builder.test(c, i);
+        }
+        let finals = builder.finalize();
+
+        // no `-` in the pattern, so completion is unobservable via results,
+        // but matching must not panic on an empty negated class.
+        assert!(finals[0].1.is_empty());
+    }
+
+    #[test]
+    fn run_drives_the_whole_source_in_one_call() {
+        let builder =
+            CondexBuilder::new(&[(Token::TagName, &["@-[abcdefghijklmnopqrstuvwxyz-]*("])]);
+
+        let source = "@hello-man(";
+
+        let finals = builder.run(source);
+
+        assert_eq!(finals[0].1, vec![vec!["hello-man"]]);
+    }
 }